@@ -2,17 +2,21 @@
 
 #[cfg(test)] extern crate parameterized_test;
 
-use clap::{AppSettings, ArgGroup, Parser};
-use std::borrow::Cow;
+use clap::{AppSettings, ArgEnum, ArgGroup, Parser};
+use portable_pty::{native_pty_system, CommandBuilder, PtySize};
+use rand::Rng;
 use std::convert::TryFrom;
 use std::ffi::{OsStr, OsString};
+use std::io::Read;
+use std::thread;
 use std::time::{Duration, Instant};
-use subprocess::{Exec, Redirection, ExitStatus, CaptureData, PopenConfig};
+use subprocess::{Exec, Redirection, ExitStatus, PopenConfig};
 use ureq::{Agent, AgentBuilder, Error, Response};
 use uuid::Uuid;
 
 static MAX_BYTES_TO_POST: usize = 10000; // not 10KB, https://healthchecks.io/docs/attaching_logs/
 static MAX_STRING_TO_LOG: usize = 1000;
+static MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
 
 /// Truncates a string for display
 fn truncate_str(s: String, max_len: usize) -> String {
@@ -34,59 +38,228 @@ fn make_user_agent(custom: Option<&str>) -> String {
     }
 }
 
+/// A fixed-capacity byte buffer that bounds memory use regardless of how much is pushed into it.
+/// In `head` mode it keeps the first `capacity` bytes seen and discards everything after; otherwise
+/// it behaves as a ring buffer, keeping only the most recently pushed `capacity` bytes.
+struct BoundedBuffer {
+    capacity: usize,
+    head: bool,
+    buf: Vec<u8>,
+}
+
+impl BoundedBuffer {
+    fn new(capacity: usize, head: bool) -> Self {
+        BoundedBuffer { capacity, head, buf: Vec::with_capacity(capacity) }
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        if self.head {
+            if self.buf.len() < self.capacity {
+                let take = (self.capacity - self.buf.len()).min(data.len());
+                self.buf.extend_from_slice(&data[..take]);
+            }
+            // else: drain-and-discard, we just don't store it
+        } else if data.len() >= self.capacity {
+            self.buf.clear();
+            self.buf.extend_from_slice(&data[data.len() - self.capacity..]);
+        } else {
+            let overflow = (self.buf.len() + data.len()).saturating_sub(self.capacity);
+            if overflow > 0 { self.buf.drain(0..overflow); }
+            self.buf.extend_from_slice(data);
+        }
+    }
+
+    fn into_vec(self) -> Vec<u8> { self.buf }
+}
+
+/// Converts captured bytes into the string that gets POST-ed, trimming the replacement chars
+/// `from_utf8_lossy` adds at a truncated multi-byte boundary. Only the leading edge needs it in
+/// tail (non-head) mode, since that's where the ring buffer may have cut us off.
+fn bytes_to_posted_string(bytes: &[u8], head: bool) -> String {
+    let s = String::from_utf8_lossy(bytes).into_owned();
+    if head { s.trim_end_matches(|c| c == '�').to_string() } else { s.trim_start_matches(|c| c == '�').to_string() }
+}
+
+/// Reads `r` to completion into a `BoundedBuffer` of capacity `MAX_BYTES_TO_POST`.
+fn read_bounded(mut r: impl Read, head: bool) -> std::io::Result<Vec<u8>> {
+    let mut buffer = BoundedBuffer::new(MAX_BYTES_TO_POST, head);
+    let mut chunk = [0u8; 8192];
+    loop {
+        match r.read(&mut chunk)? {
+            0 => return Ok(buffer.into_vec()),
+            n => buffer.push(&chunk[..n]),
+        }
+    }
+}
+
+/// The captured output of a command, either merged onto a single stream or kept separate.
+enum CapturedOutput {
+    Merged(String),
+    Split { stdout: String, stderr: String },
+}
+
+impl CapturedOutput {
+    /// Renders the captured output for the POST body. When `label` is set (i.e. `--detailed` was
+    /// also passed) split streams are rendered as distinct labeled sections so the reader can tell
+    /// which is which; otherwise stdout is followed by stderr, with no interleaving between them.
+    fn into_posted_string(self, label: bool) -> String {
+        match self {
+            CapturedOutput::Merged(s) => s,
+            CapturedOutput::Split { stdout, stderr } if label =>
+                format!("--- stdout ---\n{}\n--- stderr ---\n{}", stdout, stderr),
+            CapturedOutput::Split { stdout, stderr } => format!("{}{}", stdout, stderr),
+        }
+    }
+}
+
 /// Executes a subprocess, distilling all situations (failures, etc.) to a string of output and an
 /// exit code. This is obviously lossy, but is sufficient for our purposes. Setting verbose=true
-/// will log lost details to stderr.
-fn execute(command: &[impl AsRef<OsStr>], capture_output: bool, verbose: bool) -> (String, u8, Duration) {
+/// will log lost details to stderr. Setting pty=true runs the command attached to a pseudo-terminal
+/// instead of a plain pipe, so it sees a TTY the way it would when run directly by a human. Setting
+/// split_streams=true pipes stdout and stderr independently instead of merging them; otherwise
+/// stderr is merged into stdout as before. Output is streamed through a `BoundedBuffer` so a
+/// command that writes far more than MAX_BYTES_TO_POST can't exhaust memory, even though only the
+/// last (or, with `head`, first) slice of each stream is ever posted.
+fn execute(command: &[impl AsRef<OsStr>], capture_output: bool, verbose: bool, pty: bool, head: bool, split_streams: bool) -> (CapturedOutput, u8, Duration) {
+    if pty {
+        let (output, code, elapsed) = execute_pty(command, capture_output, verbose, head);
+        return (CapturedOutput::Merged(output), code, elapsed);
+    }
+
     let command = Exec::cmd(&command[0]).args(&command[1..])
         .stdout(Redirection::Pipe)
-        .stderr(Redirection::Merge);
+        .stderr(if split_streams { Redirection::Pipe } else { Redirection::Merge });
     if verbose { eprintln!("About to run: {:?}", command); }
 
     let start = Instant::now();
-    // TODO consider discarding stdout instead of capturing it if !capture_output;
-    // subprocess::Communicator::limit_size() can avoid unbounded memory allocation
-    let capture = command.capture();
+    let result = command.popen().and_then(|mut child| {
+        let stdout = child.stdout.take().expect("stdout should have been piped");
+        // Stderr, when piped separately, is drained on its own thread so a command that writes a
+        // lot to both streams can't deadlock us waiting on one pipe while the other fills up.
+        let stderr_reader = if split_streams {
+            let stderr = child.stderr.take().expect("stderr should have been piped");
+            Some(thread::spawn(move || read_bounded(stderr, head)))
+        } else { None };
+        let stdout_bytes = read_bounded(stdout, head)?;
+        let stderr_bytes = match stderr_reader {
+            Some(handle) => Some(handle.join().expect("stderr reader thread panicked")?),
+            None => None,
+        };
+        let exit_status = child.wait()?;
+        Ok((stdout_bytes, stderr_bytes, exit_status))
+    });
     let elapsed = start.elapsed();
 
     if verbose {
-        match &capture {
-            Ok(cap) =>
-                eprintln!("stdout+stderr:[{}] exit:{:?} runtime:{:?}",
-                          truncate_str(cap.stdout_str(), MAX_STRING_TO_LOG),
-                          cap.exit_status,
+        match &result {
+            Ok((stdout_bytes, stderr_bytes, exit_status)) =>
+                eprintln!("stdout:[{}] stderr:[{}] exit:{:?} runtime:{:?}",
+                          truncate_str(String::from_utf8_lossy(stdout_bytes).into_owned(), MAX_STRING_TO_LOG),
+                          stderr_bytes.as_deref().map(String::from_utf8_lossy).unwrap_or_default(),
+                          exit_status,
                           elapsed),
             Err(e) => eprintln!("Failed! {:?} runtime:{:?}", e, elapsed),
         };
     }
 
-    let capture = match capture {
-        Ok(cap) => cap,
-        Err(e) => CaptureData {
-            stdout: format!("{}: Command failed: {}", crate_name!(), e).bytes().collect(),
-            stderr: Vec::new(),
-            exit_status: ExitStatus::Undetermined,
-        },
+    let (stdout_bytes, stderr_bytes, exit_status) = match result {
+        Ok(ok) => ok,
+        Err(e) => (format!("{}: Command failed: {}", crate_name!(), e).into_bytes(), None, ExitStatus::Undetermined),
     };
-    assert!(capture.stderr.is_empty(), "No data should have been written to stderr");
 
-    let code = match capture.exit_status {
+    let code = match exit_status {
         ExitStatus::Exited(code) => u8::try_from(code).unwrap_or(127),
         ExitStatus::Signaled(signal) => signal + 128,
         _ => 127,
     };
-    (if capture_output { capture.stdout_str() } else { String::new() }, code, elapsed)
+
+    let output = if !capture_output {
+        if split_streams { CapturedOutput::Split { stdout: String::new(), stderr: String::new() } }
+        else { CapturedOutput::Merged(String::new()) }
+    } else {
+        match stderr_bytes {
+            Some(stderr_bytes) => CapturedOutput::Split {
+                stdout: bytes_to_posted_string(&stdout_bytes, head),
+                stderr: bytes_to_posted_string(&stderr_bytes, head),
+            },
+            None => CapturedOutput::Merged(bytes_to_posted_string(&stdout_bytes, head)),
+        }
+    };
+    (output, code, elapsed)
+}
+
+/// Like `execute`, but runs the command attached to a pseudo-terminal rather than a pipe. This
+/// naturally merges stdout and stderr, matching the default `Redirection::Merge` behavior above.
+/// Output is streamed through `read_bounded` the same as the pipe path, so a chatty command can't
+/// exhaust memory or blow through `MAX_BYTES_TO_POST` just because it's running under a PTY.
+fn execute_pty(command: &[impl AsRef<OsStr>], capture_output: bool, verbose: bool, head: bool) -> (String, u8, Duration) {
+    let pty_system = native_pty_system();
+    let pty_size = PtySize { rows: 24, cols: 80, pixel_width: 0, pixel_height: 0 };
+    let pair = match pty_system.openpty(pty_size) {
+        Ok(pair) => pair,
+        Err(e) => return (format!("{}: Failed to allocate PTY: {}", crate_name!(), e), 127, Duration::default()),
+    };
+
+    let mut cmd = CommandBuilder::new(&command[0]);
+    cmd.args(&command[1..]);
+    cmd.env("TERM", "xterm-256color");
+    if verbose { eprintln!("About to run (pty): {:?}", command[0].as_ref()); }
+
+    let start = Instant::now();
+    let mut child = match pair.slave.spawn_command(cmd) {
+        Ok(child) => child,
+        Err(e) => return (format!("{}: Command failed: {}", crate_name!(), e), 127, start.elapsed()),
+    };
+    // Drop our copy of the slave so the master's reader hits EOF once the child exits instead of
+    // blocking forever waiting for a writer that will never show up again.
+    drop(pair.slave);
+    // We never forward task-mon's own stdin to the child, so close the master's writer too; otherwise
+    // the child's stdin stays open with nothing ever feeding it, and a command that reads stdin (even
+    // just to check for EOF) hangs forever instead of running in this non-interactive, batch mode.
+    match pair.master.take_writer() {
+        Ok(writer) => drop(writer),
+        Err(e) => if verbose { eprintln!("Failed to close PTY input: {:?}", e); },
+    }
+
+    let output = match pair.master.try_clone_reader() {
+        Ok(reader) => read_bounded(reader, head).unwrap_or_default(),
+        Err(e) => {
+            if verbose { eprintln!("Failed to read PTY output: {:?}", e); }
+            Vec::new()
+        },
+    };
+
+    let status = child.wait();
+    let elapsed = start.elapsed();
+
+    if verbose {
+        match &status {
+            Ok(status) =>
+                eprintln!("stdout+stderr:[{}] exit:{:?} runtime:{:?}",
+                          truncate_str(String::from_utf8_lossy(&output).into_owned(), MAX_STRING_TO_LOG),
+                          status,
+                          elapsed),
+            Err(e) => eprintln!("Failed! {:?} runtime:{:?}", e, elapsed),
+        };
+    }
+
+    let code = match status {
+        Ok(status) => u8::try_from(status.exit_code()).unwrap_or(127),
+        Err(_) => 127,
+    };
+    (if capture_output { bytes_to_posted_string(&output, head) } else { String::new() }, code, elapsed)
 }
 
 struct HCAgent {
     agent: Agent,
     verbose: bool,
     url_prefix: String,
+    retries: u32,
+    retry_base_delay: Duration,
 }
 
 impl HCAgent {
     fn create(cli: &Cli) -> Self {
-        // TODO support retries
         // TODO could potentially shrink the binary size further by manually constructing requests with
         // https://doc.rust-lang.org/std/net/struct.TcpStream.html and https://docs.rs/native-tls/
         let agent = AgentBuilder::new()
@@ -94,37 +267,87 @@ impl HCAgent {
             .user_agent(&make_user_agent(cli.user_agent.as_deref()))
             .build();
 
-        HCAgent { agent, verbose: cli.verbose, url_prefix: cli.url_prefix() }
+        HCAgent {
+            agent,
+            verbose: cli.verbose,
+            url_prefix: cli.url_prefix(),
+            retries: cli.retries,
+            retry_base_delay: Duration::from_secs(cli.retry_base_delay),
+        }
+    }
+
+    /// Retries `send` up to `self.retries` times on transport errors or 5xx responses, sleeping
+    /// a capped exponential backoff (plus jitter) between attempts. 4xx responses are never
+    /// retried since they indicate a misconfigured UUID/slug that will never succeed.
+    fn send_with_retries<F: Fn() -> Result<Response, Error>>(&self, send: F) -> Result<Response, Error> {
+        let mut attempt = 0;
+        loop {
+            let result = send();
+            let retryable = match &result {
+                Ok(_) => false,
+                Err(Error::Transport(_)) => true,
+                Err(Error::Status(code, _)) => *code >= 500,
+            };
+            if !retryable || attempt >= self.retries {
+                return result;
+            }
+            let delay = self.backoff_delay(attempt);
+            if self.verbose {
+                eprintln!("Attempt {}/{} failed ({:?}), retrying in {:?}", attempt + 1, self.retries + 1, result, delay);
+            }
+            thread::sleep(delay);
+            attempt += 1;
+        }
+    }
+
+    /// `base * 2^attempt` plus a small random jitter, so that multiple clients retrying in lockstep
+    /// don't all hammer the server at once; the total is capped at `MAX_RETRY_DELAY`.
+    fn backoff_delay(&self, attempt: u32) -> Duration {
+        let backoff = self.retry_base_delay.saturating_mul(1 << attempt.min(16)).min(MAX_RETRY_DELAY);
+        let jitter = Duration::from_millis(rand::thread_rng().gen_range(0..250));
+        (backoff + jitter).min(MAX_RETRY_DELAY)
     }
 
     /// Pings the Healthchecks server to notify that the task denoted by the URL prefix is starting
     /// A run_id UUID is used to associate this event with its completion notification
     fn notify_start(&self, run_id: Uuid) -> Result<Response, Error> {
         let url = format!("{}/start?rid={}", self.url_prefix, run_id);
-        let req = self.agent.get(&url);
-        if self.verbose { eprintln!("Sending request: {:?}", req); }
-        req.call()
+        self.send_with_retries(|| {
+            let req = self.agent.get(&url);
+            if self.verbose { eprintln!("Sending request: {:?}", req); }
+            req.call()
+        })
     }
 
     /// Pings the Healthchecks server to notify that the task denoted by the URL prefix is done.
     /// A run_id UUID is used to associated this event with its start notification, if one was sent
     /// If code is non-zero, the task will be considered failed. If code is None the task will be logged
     /// but not update the check.
+    /// Because the same run_id is reused across retries, Healthchecks dedupes repeated completion
+    /// pings, so retrying here is safe even if an earlier attempt actually succeeded server-side.
     fn notify_complete(&self, run_id: Option<Uuid>, code: Option<u8>, output: &str) -> Result<Response, Error> {
         let mut url = format!("{}/{}", self.url_prefix, code.map(|x| x.to_string()).unwrap_or_else(|| "log".to_string()));
         if let Some(run_id) = run_id {
             url = format!("{}?rid={}", url, run_id);
         }
-        let req = self.agent.post(&url);
-        if self.verbose { eprintln!("Sending request: {:?}", req); }
-        if output.is_empty() {
-            req.call()
-        } else {
-            req.send_string(output)
-        }
+        self.send_with_retries(|| {
+            let req = self.agent.post(&url);
+            if self.verbose { eprintln!("Sending request: {:?}", req); }
+            if output.is_empty() {
+                req.call()
+            } else {
+                req.send_string(output)
+            }
+        })
     }
 }
 
+/// Output format for task-mon's own stdout, as distinct from what's POST-ed to Healthchecks.
+#[derive(ArgEnum, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Json,
+}
+
 #[derive(Parser)]
 #[clap(about, version)]
 #[clap(setting = AppSettings::DeriveDisplayOrder)]
@@ -151,6 +374,16 @@ struct Cli {
     #[clap(long)]
     head: bool,
 
+    /// Run the command attached to a pseudo-terminal instead of a plain pipe, so it sees a TTY
+    /// (enabling color, progress bars, etc.) the way it would when run directly by a human
+    #[clap(long, conflicts_with="split-streams")]
+    pty: bool,
+
+    /// Capture stdout and stderr on separate streams instead of merging them; combine with
+    /// --detailed to render each as a distinct labeled section in the information POST-ed
+    #[clap(long, conflicts_with="pty")]
+    split_streams: bool,
+
     /// Don't POST any output from the command
     #[clap(long, conflicts_with_all=&["detailed", "env"])]
     ping_only: bool,
@@ -171,10 +404,23 @@ struct Cli {
     #[clap(long)]
     verbose: bool,
 
+    /// Print a JSON summary of the run to task-mon's own stdout once it completes, so wrapping
+    /// automation can learn the outcome without scraping --verbose's stderr output
+    #[clap(long, arg_enum, value_name="FORMAT")]
+    format: Option<OutputFormat>,
+
     /// Customize the user-agent string sent to the Healthchecks.io server
     #[clap(long, value_name="USER_AGENT")]
     user_agent: Option<String>,
 
+    /// Number of times to retry a ping after a dropped connection or 5xx response
+    #[clap(long, value_name="N", default_value_t=3)]
+    retries: u32,
+
+    /// Base delay, in seconds, for exponential backoff between retries
+    #[clap(long, value_name="SECONDS", default_value_t=1)]
+    retry_base_delay: u64,
+
     /// Base URL of the Healthchecks.io server to ping
     #[clap(long, env="HEALTHCHECKS_BASE_URL", default_value="https://hc-ping.com")]
     base_url: String,
@@ -199,21 +445,80 @@ impl Cli {
     }
 }
 
-fn run(cli: Cli, agent: HCAgent) -> Result<Response, Error> {
-    let mut maybe_run_id = None;  // Don't bother reporting a run ID unless we're sending a start ping
+/// Everything about a single run that `--format json` reports, gathered up so `main` can print a
+/// summary after `run` returns instead of swallowing the result into a bare `.expect(...)`.
+struct RunResult {
+    command: Vec<String>,
+    url: String,
+    run_id: Option<Uuid>,
+    exit_code: u8,
+    duration_ms: u128,
+    start: Option<Result<Response, Error>>,
+    complete: Result<Response, Error>,
+}
+
+/// Minimal JSON string escaping; avoids pulling in serde_json for a single small object.
+fn json_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    format!("\"{}\"", out)
+}
+
+/// Renders a ping's result as `{"succeeded":bool,"status":number|null}`; the status is the HTTP
+/// status code when one was received, or null for a transport-level failure (e.g. dropped connection).
+fn ping_outcome_json(result: &Result<Response, Error>) -> String {
+    match result {
+        Ok(resp) => format!("{{\"succeeded\":true,\"status\":{}}}", resp.status()),
+        Err(Error::Status(code, _)) => format!("{{\"succeeded\":false,\"status\":{}}}", code),
+        Err(Error::Transport(_)) => "{\"succeeded\":false,\"status\":null}".to_string(),
+    }
+}
+
+impl RunResult {
+    fn to_json(&self) -> String {
+        let command = self.command.iter().map(|s| json_escape(s)).collect::<Vec<_>>().join(",");
+        let run_id = self.run_id.map(|id| json_escape(&id.to_string())).unwrap_or_else(|| "null".to_string());
+        let start = self.start.as_ref().map(ping_outcome_json).unwrap_or_else(|| "null".to_string());
+        format!(
+            "{{\"command\":[{}],\"url\":{},\"exit_code\":{},\"duration_ms\":{},\"run_id\":{},\"start\":{},\"complete\":{}}}",
+            command, json_escape(&self.url), self.exit_code, self.duration_ms, run_id, start, ping_outcome_json(&self.complete),
+        )
+    }
+}
+
+fn run(cli: Cli, agent: HCAgent) -> RunResult {
+    let command = cli.command.iter().map(|s| s.to_string_lossy().into_owned()).collect();
+    let url = agent.url_prefix.clone();
+
+    let mut run_id = None;  // Don't bother reporting a run ID unless we're sending a start ping
+    let mut start = None;
     if cli.time {
-        let run_id = Uuid::new_v4();
-        maybe_run_id = Some(run_id);
-        if let Err(e) = agent.notify_start(run_id) {
+        let id = Uuid::new_v4();
+        run_id = Some(id);
+        let result = agent.notify_start(id);
+        if let Err(e) = &result {
             eprintln!("Failed to send start request: {:?}", e);
         }
+        start = Some(result);
     }
-    let (mut output, code, elapsed) = execute(&cli.command, !cli.ping_only, cli.verbose);
+    let (captured, code, elapsed) = execute(&cli.command, !cli.ping_only, cli.verbose, cli.pty, cli.head, cli.split_streams);
+    let mut output = captured.into_posted_string(cli.detailed);
 
     if cli.detailed {
         // We could properly escape command, e.g. with https://crates.io/crates/shell-quote
-        output = format!("$ {} 2>&1\n{}\n\nExit Code: {}\nDuration: {:?}",
-                         cli.command.join(OsStr::new(" ")).to_string_lossy(), output, code, elapsed);
+        let redirect = if cli.split_streams { "" } else { " 2>&1" };
+        output = format!("$ {}{}\n{}\n\nExit Code: {}\nDuration: {:?}",
+                         cli.command.join(OsStr::new(" ")).to_string_lossy(), redirect, output, code, elapsed);
         if cli.env {
             let env_str = PopenConfig::current_env().iter()
                 .map(|(k, v)| format!("{}={}", k.to_string_lossy(), v.to_string_lossy()))
@@ -222,23 +527,22 @@ fn run(cli: Cli, agent: HCAgent) -> Result<Response, Error> {
         }
     }
 
-    // If we have too much output safely convert the last 10k bytes into UTF-8
-    let output =
-        if !cli.head && output.len() > MAX_BYTES_TO_POST {
-            String::from_utf8_lossy(&output.as_bytes()[output.len() - MAX_BYTES_TO_POST..])
-        } else { Cow::Owned(output) };
+    let ping_code = if cli.log { None } else { Some(code) };
+    let complete = agent.notify_complete(run_id, ping_code, &output);
 
-    // Trim replacement chars added by from_utf8_lossy since they are multi-byte and can actually
-    // increase the length of the string.
-    let code = if cli.log { None } else { Some(code) };
-    agent.notify_complete(maybe_run_id, code, output.trim_start_matches(|c| c=='�'))
+    RunResult { command, url, run_id, exit_code: code, duration_ms: elapsed.as_millis(), start, complete }
 }
 
 fn main() {
     let cli = Cli::parse();
+    let format = cli.format;
     let agent = HCAgent::create(&cli);
 
-    run(cli, agent).expect("Failed to reach Healthchecks.io");
+    let result = run(cli, agent);
+    if format == Some(OutputFormat::Json) {
+        println!("{}", result.to_json());
+    }
+    result.complete.expect("Failed to reach Healthchecks.io");
 }
 
 #[cfg(test)]
@@ -291,7 +595,13 @@ mod tests {
             .match_query(mockito::Matcher::Regex("rid=.*".into()))
             .match_body("run id")
             .with_status(200).create();
-        let agent = HCAgent{ agent: Agent::new(), verbose: false, url_prefix: format!("{}/{}", mockito::server_url(), "ping") };
+        let agent = HCAgent{
+            agent: Agent::new(),
+            verbose: false,
+            url_prefix: format!("{}/{}", mockito::server_url(), "ping"),
+            retries: 0,
+            retry_base_delay: Duration::from_secs(0),
+        };
         let suc_response = agent.notify_complete(None, Some(0), "foo bar");
         let fail_response = agent.notify_complete(None, Some(10), "bar baz");
         let log_response = agent.notify_complete(None, None, "bang boom");
@@ -316,12 +626,17 @@ mod tests {
                 ping_key: None,
                 time: false,
                 head: false,
+                pty: false,
+                split_streams: false,
                 ping_only: false,
                 log: false,
                 detailed: false,
                 env: false,
                 verbose: false,
+                format: None,
                 user_agent: None,
+                retries: 0,
+                retry_base_delay: 1,
                 base_url: mockito::server_url(),
                 command: command.iter().map(OsString::from).collect(),
             }
@@ -335,7 +650,7 @@ mod tests {
             let agent = HCAgent::create(&cli);
             let res = run(cli, agent);
             m.assert();
-            res.unwrap();
+            res.complete.unwrap();
         }
 
         #[test]
@@ -348,7 +663,7 @@ mod tests {
 
             let res = run(cli, agent);
             m.assert();
-            res.unwrap();
+            res.complete.unwrap();
         }
 
         #[test]
@@ -375,7 +690,7 @@ mod tests {
 
             let res = run(cli, agent);
             m.assert();
-            res.unwrap();
+            res.complete.unwrap();
         }
 
         #[test]
@@ -391,7 +706,7 @@ mod tests {
 
             let res = run(cli, agent);
             m.assert();
-            res.unwrap();
+            res.complete.unwrap();
         }
 
         #[test]
@@ -402,10 +717,40 @@ mod tests {
             let cli = fake_cli("unreachable", &["true"]);
             let agent = HCAgent::create(&cli);
 
-            run(cli, agent).expect_err("Should fail.");
+            run(cli, agent).complete.expect_err("Should fail.");
             m.expect(0);
         }
 
+        #[test]
+        fn retries_exhausted() {
+            let m = mockito::mock("POST", "/retries_exhausted/0")
+                .match_body("oops").with_status(500).expect(3).create();
+
+            let mut cli = fake_cli("retries_exhausted", &["true"]);
+            cli.retries = 2;
+            cli.retry_base_delay = 0;
+            let agent = HCAgent::create(&cli);
+
+            let res = agent.notify_complete(None, Some(0), "oops");
+            m.assert();
+            assert!(res.is_err());
+        }
+
+        #[test]
+        fn no_retry_on_4xx() {
+            let m = mockito::mock("POST", "/no_retry_on_4xx/1")
+                .match_body("bad").with_status(400).expect(1).create();
+
+            let mut cli = fake_cli("no_retry_on_4xx", &["true"]);
+            cli.retries = 2;
+            cli.retry_base_delay = 0;
+            let agent = HCAgent::create(&cli);
+
+            let res = agent.notify_complete(None, Some(1), "bad");
+            m.assert();
+            assert!(res.is_err());
+        }
+
         #[test]
         fn timed() {
             let start_m = mockito::mock("GET", "/timed/start")
@@ -423,7 +768,7 @@ mod tests {
             let res = run(cli, agent);
             start_m.assert();
             done_m.assert();
-            res.unwrap();
+            res.complete.unwrap();
         }
 
         #[test]
@@ -447,7 +792,39 @@ mod tests {
 
             let res = run(cli, agent);
             m.assert();
-            res.unwrap();
+            res.complete.unwrap();
+        }
+
+        #[test]
+        fn pty() {
+            let m = mockito::mock("POST", "/pty/0")
+                .match_body(mockito::Matcher::Regex("hello".to_string()))
+                .with_status(200).create();
+
+            let mut cli = fake_cli("pty", &["echo", "hello"]);
+            cli.pty = true;
+            let agent = HCAgent::create(&cli);
+
+            let res = run(cli, agent);
+            m.assert();
+            res.complete.unwrap();
+        }
+
+        #[test]
+        fn pty_stdin_eof() {
+            // A command that reads its own stdin should see immediate EOF rather than hang, since
+            // task-mon never forwards its own stdin into the PTY.
+            let m = mockito::mock("POST", "/pty-stdin-eof/0")
+                .match_body(mockito::Matcher::Regex("done".to_string()))
+                .with_status(200).create();
+
+            let mut cli = fake_cli("pty-stdin-eof", &["bash", "-c", "cat; echo done"]);
+            cli.pty = true;
+            let agent = HCAgent::create(&cli);
+
+            let res = run(cli, agent);
+            m.assert();
+            res.complete.unwrap();
         }
 
         #[test]
@@ -461,7 +838,7 @@ mod tests {
 
             let res = run(cli, agent);
             m.assert();
-            res.unwrap();
+            res.complete.unwrap();
         }
 
         #[test] fn detailed() {
@@ -476,7 +853,49 @@ mod tests {
 
             let res = run(cli, agent);
             m.assert();
-            res.unwrap();
+            res.complete.unwrap();
+        }
+
+        #[test] fn split_streams_detailed() {
+            let m = mockito::mock("POST", "/split_streams_detailed/5")
+                .match_body(mockito::Matcher::Regex(
+                    "^\\$ bash -c .*\n--- stdout ---\nout\n\n--- stderr ---\nerr\n\n\nExit Code: 5\nDuration: .*$".to_string()))
+                .with_status(200).create();
+
+            let mut cli = fake_cli("split_streams_detailed", &["bash", "-c", "echo out; echo err >&2; exit 5"]);
+            cli.split_streams = true;
+            cli.detailed = true;
+            let agent = HCAgent::create(&cli);
+
+            let res = run(cli, agent);
+            m.assert();
+            res.complete.unwrap();
+        }
+
+        #[test]
+        fn json_summary() {
+            let start_m = mockito::mock("GET", "/json_summary/start")
+                .match_query(mockito::Matcher::Regex("rid=.*".into()))
+                .with_status(200).create();
+            let done_m = mockito::mock("POST", "/json_summary/5")
+                .match_query(mockito::Matcher::Regex("rid=.*".into()))
+                .with_status(200).create();
+
+            let mut cli = fake_cli("json_summary", &["bash", "-c", "exit 5"]);
+            cli.time = true;
+            let agent = HCAgent::create(&cli);
+
+            let res = run(cli, agent);
+            start_m.assert();
+            done_m.assert();
+            assert_eq!(res.exit_code, 5);
+            assert!(res.run_id.is_some());
+
+            let json = res.to_json();
+            assert!(json.contains("\"exit_code\":5"));
+            assert!(json.contains(&format!("\"url\":\"{}/json_summary\"", mockito::server_url())));
+            assert!(json.contains("\"start\":{\"succeeded\":true,\"status\":200}"));
+            assert!(json.contains("\"complete\":{\"succeeded\":true,\"status\":200}"));
         }
     }
 }